@@ -0,0 +1,58 @@
+//! Provider configuration types and the runtime registry built from them.
+//!
+//! This module owns the on-disk/admin-API config shape ([`ProviderConfig`])
+//! that [`registry::ProviderRegistry`] builds live providers from. The
+//! concrete provider implementations ([`AnthropicProvider`],
+//! [`AnthropicCompatibleProvider`], [`OpenAIProvider`]) and
+//! [`error::ProviderError`] live in sibling files not touched by this change.
+
+pub mod registry;
+
+pub use registry::ProviderRegistry;
+
+use serde::{Deserialize, Serialize};
+
+/// How a provider authenticates outbound requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthType {
+    ApiKey,
+    OAuth,
+}
+
+/// A single `[[providers]]` config block: one provider's connection,
+/// authentication, and per-provider breaker tuning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub provider_type: String,
+    pub auth_type: AuthType,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_key_path: Option<String>,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub oauth_provider: Option<String>,
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Consecutive failures before this provider's circuit breaker trips.
+    /// Falls back to the registry's default threshold when unset.
+    #[serde(default)]
+    pub failure_threshold: Option<u32>,
+    /// Cooldown, in seconds, before a tripped breaker admits a trial
+    /// request. Falls back to the registry's default cooldown when unset.
+    #[serde(default)]
+    pub cooldown_secs: Option<u64>,
+}
+
+impl ProviderConfig {
+    /// Whether this provider should be instantiated/kept live. Defaults to
+    /// `true` when unset so existing configs without the field keep working.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
@@ -3,35 +3,672 @@ use super::{
     ProviderConfig,
 };
 use crate::auth::TokenStore;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-/// Provider registry that manages all configured providers
+/// Default number of consecutive failures that trips a provider's breaker.
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+/// Default cooldown before a tripped breaker allows a trial request.
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+/// Upper bound the cooldown is doubled towards on repeated failures.
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Fallback characters-per-token ratio for model families we have no dedicated
+/// tokenizer for (~4 characters per token).
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Per-model context-window metadata, sourced from the `[[models]]` config.
+#[derive(Clone, Copy, Debug)]
+pub struct ModelLimits {
+    /// Maximum number of tokens the model accepts in a single request.
+    pub max_context_tokens: usize,
+    /// Maximum number of tokens the model will emit, if the provider caps it.
+    pub max_output_tokens: Option<usize>,
+}
+
+/// One provider entry within a `[[models]]` config block: a configured
+/// provider that serves the logical model, plus its routing metadata.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ModelProviderConfig {
+    /// Name of a configured [`ProviderConfig`] that serves this model.
+    pub provider: String,
+    /// Relative weight for weighted load balancing (0 is treated as 1).
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Optional per-provider rate cap in requests per second; `None` is
+    /// unlimited.
+    #[serde(default)]
+    pub rate_cap: Option<u32>,
+    /// Maximum number of tokens this provider's model accepts in one request.
+    pub max_context_tokens: usize,
+    /// Maximum number of tokens the provider's model will emit, if capped.
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A `[[models]]` config block: one logical model name mapped to an ordered,
+/// priority-first list of providers that can serve it. The list order drives
+/// failover; the per-entry weight drives load balancing.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ModelConfig {
+    pub name: String,
+    pub providers: Vec<ModelProviderConfig>,
+}
+
+/// Estimates the number of tokens in a piece of text. Different model families
+/// pack text differently, so routing consults the tokenizer matching the
+/// target model rather than a single global ratio.
+trait Tokenizer {
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// Cheap, dependency-free fallback: estimate tokens from character count. Used
+/// for model families without a dedicated BPE tokenizer.
+struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(CHARS_PER_TOKEN)
+    }
+}
+
+/// Approximate BPE tokenizer for OpenAI/Anthropic-family models. Counts
+/// whitespace- and punctuation-delimited word pieces, which tracks real BPE
+/// output far better than the raw char heuristic without vendoring the full
+/// merge tables.
+struct BpeTokenizer;
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        let mut tokens = 0usize;
+        let mut in_word = false;
+        for ch in text.chars() {
+            if is_cjk(ch) {
+                // CJK ideographs don't word-segment on whitespace and BPE emits
+                // roughly one token per character, so count each individually
+                // rather than collapsing a run into a single word token.
+                in_word = false;
+                tokens += 1;
+            } else if ch.is_alphanumeric() {
+                if !in_word {
+                    tokens += 1;
+                    in_word = true;
+                }
+            } else {
+                in_word = false;
+                if !ch.is_whitespace() {
+                    // Punctuation and symbols are usually their own token.
+                    tokens += 1;
+                }
+            }
+        }
+        tokens.max(1)
+    }
+}
+
+/// Whether a character is a CJK (Chinese/Japanese/Korean) ideograph, kana, or
+/// Hangul syllable —
+/// scripts that `char::is_alphanumeric` reports as word characters but that BPE
+/// tokenizers split per-character rather than per-whitespace-run.
+fn is_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x3040..=0x30FF       // Hiragana + Katakana
+        | 0x3400..=0x4DBF     // CJK Unified Ideographs Extension A
+        | 0x4E00..=0x9FFF     // CJK Unified Ideographs
+        | 0xF900..=0xFAFF     // CJK Compatibility Ideographs
+        | 0xAC00..=0xD7AF     // Hangul syllables
+        | 0x20000..=0x2EBEF   // CJK Unified Ideographs Extensions B–F
+    )
+}
+
+/// Pick a tokenizer for a logical model name.
+fn tokenizer_for_model(model: &str) -> Box<dyn Tokenizer> {
+    let model = model.to_ascii_lowercase();
+    if model.contains("gpt")
+        || model.contains("claude")
+        || model.contains("o1")
+        || model.contains("o3")
+    {
+        Box::new(BpeTokenizer)
+    } else {
+        Box::new(HeuristicTokenizer)
+    }
+}
+
+/// Estimate the prompt token count of an incoming Anthropic-style request
+/// payload by tokenizing its system prompt and message contents.
+fn estimate_payload_tokens(model: &str, payload: &Value) -> usize {
+    let tokenizer = tokenizer_for_model(model);
+    let mut text = String::new();
+
+    collect_text(payload.get("system"), &mut text);
+    if let Some(messages) = payload.get("messages").and_then(|m| m.as_array()) {
+        for message in messages {
+            collect_text(message.get("content"), &mut text);
+        }
+    }
+
+    tokenizer.count_tokens(&text)
+}
+
+/// Recursively gather string leaves from a request fragment (`content` may be a
+/// bare string or an array of content blocks).
+fn collect_text(value: Option<&Value>, out: &mut String) {
+    match value {
+        Some(Value::String(s)) => {
+            out.push_str(s);
+            out.push('\n');
+        }
+        Some(Value::Array(arr)) => {
+            for v in arr {
+                collect_text(Some(v), out);
+            }
+        }
+        Some(Value::Object(map)) => {
+            if let Some(Value::String(s)) = map.get("text") {
+                out.push_str(s);
+                out.push('\n');
+            }
+            // `tool_result`/`tool_use` blocks nest their payload under `content`
+            // (a string or another array of blocks); recurse into it so tool
+            // traffic — the bulk of this proxy's agent workload — is actually
+            // counted instead of estimating as a single token.
+            if let Some(content) = map.get("content") {
+                collect_text(Some(content), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// State of a provider's circuit breaker.
+enum BreakerState {
+    /// Healthy; requests flow normally.
+    Closed,
+    /// Tripped; requests are skipped until `cooldown` elapses from `opened_at`.
+    Open { opened_at: Instant, cooldown: Duration },
+    /// Cooldown elapsed; a single trial request is allowed through.
+    HalfOpen { cooldown: Duration },
+}
+
+/// Per-provider circuit breaker. Counts consecutive failures and takes the
+/// provider out of rotation once `threshold` is crossed, letting a single
+/// trial request through after `base_cooldown` that doubles on repeated
+/// failure up to [`MAX_COOLDOWN`]. Both `threshold` and `base_cooldown`
+/// default to [`DEFAULT_FAILURE_THRESHOLD`]/[`DEFAULT_COOLDOWN`] but are
+/// overridable per provider (see [`ProviderEntry::new`]).
+struct CircuitBreaker {
+    consecutive_failures: AtomicU32,
+    threshold: u32,
+    base_cooldown: Duration,
+    state: Mutex<BreakerState>,
+    /// Set while a half-open trial request is outstanding, so exactly one probe
+    /// is admitted until it reports success or failure.
+    half_open_in_flight: AtomicBool,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, base_cooldown: Duration) -> Self {
+        Self {
+            consecutive_failures: AtomicU32::new(0),
+            threshold,
+            base_cooldown,
+            state: Mutex::new(BreakerState::Closed),
+            half_open_in_flight: AtomicBool::new(false),
+        }
+    }
+
+    /// Update the threshold/cooldown a live breaker enforces, without
+    /// resetting its current trip state. Used on reload so editing
+    /// `failure_threshold`/`cooldown_secs` on disk takes effect without
+    /// rebuilding the provider (and dropping an open breaker's cooldown).
+    fn reconfigure(&mut self, threshold: u32, base_cooldown: Duration) {
+        self.threshold = threshold;
+        self.base_cooldown = base_cooldown;
+    }
+
+    /// Side-effect-free check of whether the breaker *would* admit a request
+    /// right now. Used to filter/weight candidates during enumeration without
+    /// tripping `Open` → `HalfOpen` or consuming the single trial slot.
+    fn would_admit(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed => true,
+            BreakerState::HalfOpen { .. } => !self.half_open_in_flight.load(Ordering::Acquire),
+            BreakerState::Open {
+                opened_at,
+                cooldown,
+            } => opened_at.elapsed() >= cooldown,
+        }
+    }
+
+    /// Reserve an admission slot immediately before dispatching. Transitions an
+    /// expired `Open` breaker to `HalfOpen` and admits exactly one half-open
+    /// trial via a compare-and-swap on [`Self::half_open_in_flight`]; concurrent
+    /// callers that lose the swap get `false` and move to another candidate.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed => true,
+            BreakerState::Open {
+                opened_at,
+                cooldown,
+            } => {
+                if opened_at.elapsed() >= cooldown {
+                    *state = BreakerState::HalfOpen { cooldown };
+                    // We are the first (and only) trial request.
+                    self.half_open_in_flight.store(true, Ordering::Release);
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerState::HalfOpen { .. } => self
+                .half_open_in_flight
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok(),
+        }
+    }
+
+    /// Record a successful request: reset the failure count and close.
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.state.lock().unwrap() = BreakerState::Closed;
+        self.half_open_in_flight.store(false, Ordering::Release);
+    }
+
+    /// Record a failed request: trip the breaker once the threshold is crossed,
+    /// or re-open (with doubled cooldown) if a half-open trial failed.
+    fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut state = self.state.lock().unwrap();
+        let next_cooldown = match *state {
+            BreakerState::HalfOpen { cooldown } => (cooldown * 2).min(MAX_COOLDOWN),
+            _ => self.base_cooldown,
+        };
+        if matches!(*state, BreakerState::HalfOpen { .. }) || failures >= self.threshold {
+            *state = BreakerState::Open {
+                opened_at: Instant::now(),
+                cooldown: next_cooldown,
+            };
+        }
+        self.half_open_in_flight.store(false, Ordering::Release);
+    }
+}
+
+/// Per-(provider, model) counters and latency accumulator.
+///
+/// Stored behind an [`Arc`] so a series can be updated after the `series` map
+/// lock has been released.
+#[derive(Default)]
+struct SeriesMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    retries: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    latency_ms_sum: AtomicU64,
+    latency_count: AtomicU64,
+}
+
+/// A Prometheus counter's name, HELP text, and accessor, as rendered by
+/// [`RegistryMetrics::render_prometheus`].
+type CounterSpec = (&'static str, &'static str, fn(&SeriesMetrics) -> u64);
+
+/// Observability handle for the router. The registry owns one and exposes a
+/// clone through [`ProviderRegistry::metrics`]; request/error/token numbers
+/// are attributed to a `provider_name` / `model` series by the names passed
+/// to each `record_*` call. Providers built by the registry are not given a
+/// handle of their own.
+///
+/// Only a Prometheus text exporter ([`Self::render_prometheus`]) is
+/// implemented here. An OTLP exporter is not — wiring one up means adding an
+/// `opentelemetry`/`opentelemetry-otlp` dependency and a config toggle for
+/// the collector endpoint, which is out of scope for this change; this
+/// struct's per-series counters are shaped so a future OTLP exporter can read
+/// from the same `series` map rather than needing its own recording path.
+#[derive(Default)]
+pub struct RegistryMetrics {
+    series: Mutex<HashMap<(String, String), Arc<SeriesMetrics>>>,
+    configured_providers: AtomicU64,
+    healthy_providers: AtomicU64,
+}
+
+impl RegistryMetrics {
+    fn series(&self, provider: &str, model: &str) -> Arc<SeriesMetrics> {
+        let mut series = self.series.lock().unwrap();
+        series
+            .entry((provider.to_string(), model.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// Record a dispatched request for `provider`/`model`.
+    pub fn record_request(&self, provider: &str, model: &str) {
+        self.series(provider, model)
+            .requests
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a failed request.
+    pub fn record_error(&self, provider: &str, model: &str) {
+        self.series(provider, model)
+            .errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a retry attempt.
+    pub fn record_retry(&self, provider: &str, model: &str) {
+        self.series(provider, model)
+            .retries
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record prompt/completion token usage parsed from a provider response.
+    pub fn record_tokens(&self, provider: &str, model: &str, prompt: u64, completion: u64) {
+        let s = self.series(provider, model);
+        s.prompt_tokens.fetch_add(prompt, Ordering::Relaxed);
+        s.completion_tokens.fetch_add(completion, Ordering::Relaxed);
+    }
+
+    /// Observe request latency in milliseconds (sum + count back a histogram
+    /// average; [`Self::render_prometheus`] also emits a single `+Inf`
+    /// bucket row so the series is valid Prometheus histogram exposition).
+    pub fn observe_latency_ms(&self, provider: &str, model: &str, ms: u64) {
+        let s = self.series(provider, model);
+        s.latency_ms_sum.fetch_add(ms, Ordering::Relaxed);
+        s.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn set_gauges(&self, configured: u64, healthy: u64) {
+        self.configured_providers
+            .store(configured, Ordering::Relaxed);
+        self.healthy_providers.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Render all series in Prometheus text exposition format. Backs `/metrics`.
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP ccmux_providers_configured Number of configured providers\n");
+        out.push_str("# TYPE ccmux_providers_configured gauge\n");
+        out.push_str(&format!(
+            "ccmux_providers_configured {}\n",
+            self.configured_providers.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP ccmux_providers_healthy Number of healthy providers\n");
+        out.push_str("# TYPE ccmux_providers_healthy gauge\n");
+        out.push_str(&format!(
+            "ccmux_providers_healthy {}\n",
+            self.healthy_providers.load(Ordering::Relaxed)
+        ));
+
+        let series = self.series.lock().unwrap();
+        // Precompute the label set for each series once, preserving a stable
+        // pairing with its metrics handle.
+        let rows: Vec<(String, &Arc<SeriesMetrics>)> = series
+            .iter()
+            .map(|((provider, model), m)| {
+                (
+                    format!("provider_name=\"{}\",model=\"{}\"", provider, model),
+                    m,
+                )
+            })
+            .collect();
+
+        // Prometheus exposition requires a single HELP/TYPE per metric name with
+        // all of its samples grouped together, so emit the header once and then
+        // loop every series under it.
+        let counters: [CounterSpec; 5] = [
+            ("ccmux_requests_total", "Requests dispatched", |m| {
+                m.requests.load(Ordering::Relaxed)
+            }),
+            ("ccmux_errors_total", "Requests that errored", |m| {
+                m.errors.load(Ordering::Relaxed)
+            }),
+            ("ccmux_retries_total", "Retry attempts", |m| {
+                m.retries.load(Ordering::Relaxed)
+            }),
+            ("ccmux_prompt_tokens_total", "Prompt tokens consumed", |m| {
+                m.prompt_tokens.load(Ordering::Relaxed)
+            }),
+            (
+                "ccmux_completion_tokens_total",
+                "Completion tokens produced",
+                |m| m.completion_tokens.load(Ordering::Relaxed),
+            ),
+        ];
+        for (metric, help, get) in counters {
+            out.push_str(&format!("# HELP {metric} {help}\n# TYPE {metric} counter\n"));
+            for (labels, m) in &rows {
+                out.push_str(&format!("{metric}{{{labels}}} {}\n", get(m)));
+            }
+        }
+
+        out.push_str("# HELP ccmux_latency_ms Request latency in milliseconds\n");
+        out.push_str("# TYPE ccmux_latency_ms histogram\n");
+        for (labels, m) in &rows {
+            // A single `+Inf` bucket (every observation falls in it) is the
+            // minimum valid histogram exposition; we don't track real bucket
+            // boundaries, but `_sum`/`_count` alone isn't a valid histogram.
+            out.push_str(&format!(
+                "ccmux_latency_ms_bucket{{{labels},le=\"+Inf\"}} {}\n",
+                m.latency_count.load(Ordering::Relaxed)
+            ));
+            out.push_str(&format!(
+                "ccmux_latency_ms_sum{{{labels}}} {}\n",
+                m.latency_ms_sum.load(Ordering::Relaxed)
+            ));
+            out.push_str(&format!(
+                "ccmux_latency_ms_count{{{labels}}} {}\n",
+                m.latency_count.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+/// A registered provider together with the config it was built from and its
+/// current enabled state. Keeping the originating [`ProviderConfig`] lets the
+/// admin API report what a provider was configured with and lets
+/// [`ProviderRegistry::reload`] diff a new config against the one the live
+/// provider was built from. Toggling the enabled state keeps the instance.
+struct ProviderEntry {
+    provider: Arc<Box<dyn AnthropicProvider>>,
+    config: ProviderConfig,
+    enabled: bool,
+    breaker: CircuitBreaker,
+}
+
+impl ProviderEntry {
+    /// Build an entry, sizing its circuit breaker from `config.failure_threshold`
+    /// / `config.cooldown_secs` when set, falling back to
+    /// [`DEFAULT_FAILURE_THRESHOLD`]/[`DEFAULT_COOLDOWN`] otherwise.
+    fn new(provider: Box<dyn AnthropicProvider>, config: ProviderConfig, enabled: bool) -> Self {
+        let threshold = config.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+        let cooldown = config
+            .cooldown_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_COOLDOWN);
+        Self {
+            provider: Arc::new(provider),
+            config,
+            enabled,
+            breaker: CircuitBreaker::new(threshold, cooldown),
+        }
+    }
+
+    /// Side-effect-free routing filter: a provider is a candidate when it is
+    /// enabled and its breaker would currently admit a request. Used when
+    /// enumerating/weighting candidates, where no dispatch happens yet.
+    fn would_route(&self) -> bool {
+        self.enabled && self.breaker.would_admit()
+    }
+
+    /// Reserve a dispatch slot on this provider, consuming the breaker's single
+    /// half-open trial when applicable. Called only when a request is about to
+    /// be sent to this provider.
+    fn try_dispatch(&self) -> bool {
+        self.enabled && self.breaker.try_acquire()
+    }
+}
+
+/// Mutable state of the registry, guarded by a single [`RwLock`] so the
+/// provider set can be inspected and mutated at runtime without restarting
+/// the proxy.
+#[derive(Default)]
+struct RegistryInner {
+    /// Map of provider name -> provider entry
+    providers: HashMap<String, ProviderEntry>,
+    /// Map of model name -> ordered list of candidate providers. The order is
+    /// the priority order used for failover; the per-candidate weight is used
+    /// for load balancing across providers serving the same model.
+    model_to_provider: HashMap<String, Vec<Candidate>>,
+    /// Context-window metadata per (provider, logical model), used for
+    /// token-aware routing.
+    model_limits: HashMap<(String, String), ModelLimits>,
+}
+
+/// A provider serving a logical model, with its relative weight for load
+/// balancing and an optional rate cap.
+struct Candidate {
+    provider: String,
+    /// Relative weight for weighted selection (0 is treated as 1).
+    weight: u32,
+    /// Optional per-provider rate cap (requests per second); `None` is
+    /// unlimited.
+    rate_cap: Option<u32>,
+    /// Smooth weighted round-robin accumulator.
+    current_weight: AtomicI64,
+    /// Fixed-window limiter enforcing `rate_cap`, rebuilt whenever `rate_cap`
+    /// changes; `None` when the candidate is uncapped.
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl Candidate {
+    fn new(provider: String, weight: u32, rate_cap: Option<u32>) -> Self {
+        Self {
+            provider,
+            weight: weight.max(1),
+            rate_cap,
+            current_weight: AtomicI64::new(0),
+            rate_limiter: rate_cap.map(RateLimiter::new),
+        }
+    }
+
+    /// Side-effect-free peek at whether `rate_cap`'s current window still has
+    /// budget. Mirrors [`ProviderEntry::would_route`]'s breaker check — used by
+    /// enumeration paths that must not consume state.
+    fn rate_would_admit(&self) -> bool {
+        self.rate_limiter.as_ref().is_none_or(RateLimiter::peek)
+    }
+
+    /// Reserve a slot in `rate_cap`'s current window. Called only when a
+    /// request is about to be dispatched to this candidate.
+    fn try_acquire_rate(&self) -> bool {
+        self.rate_limiter.as_ref().is_none_or(RateLimiter::try_acquire)
+    }
+}
+
+/// Fixed-window rate limiter backing a [`Candidate`]'s `rate_cap`: allows up to
+/// `cap` requests per one-second window, the window restarting once a second
+/// has elapsed since it began.
+struct RateLimiter {
+    cap: u32,
+    window: Mutex<(Instant, u32)>,
+}
+
+impl RateLimiter {
+    fn new(cap: u32) -> Self {
+        Self {
+            cap: cap.max(1),
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Roll the window over if a second has elapsed, then report whether it
+    /// still has budget without consuming any.
+    fn peek(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        Self::roll(&mut window);
+        window.1 < self.cap
+    }
+
+    /// Roll the window over if a second has elapsed, then consume a slot if
+    /// one is available.
+    fn try_acquire(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        Self::roll(&mut window);
+        if window.1 < self.cap {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn roll(window: &mut (Instant, u32)) {
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+    }
+}
+
+/// Provider registry that manages all configured providers.
+///
+/// The registry is interior-mutable: providers can be added, removed, and
+/// enabled/disabled at runtime through the admin API (see [`Self::add_provider`],
+/// [`Self::remove_provider`], [`Self::set_enabled`]) so operators can perform
+/// live provider rollouts and API-key rotation without a restart.
 pub struct ProviderRegistry {
-    /// Map of provider name -> provider instance
-    providers: HashMap<String, Arc<Box<dyn AnthropicProvider>>>,
-    /// Map of model name -> provider name for fast lookup
-    model_to_provider: HashMap<String, String>,
+    inner: RwLock<RegistryInner>,
+    /// Shared observability handle, attributed by `provider_name` / `model`.
+    metrics: Arc<RegistryMetrics>,
 }
 
 impl ProviderRegistry {
     /// Create a new empty registry
     pub fn new() -> Self {
         Self {
-            providers: HashMap::new(),
-            model_to_provider: HashMap::new(),
+            inner: RwLock::new(RegistryInner::default()),
+            metrics: Arc::new(RegistryMetrics::default()),
         }
     }
 
-    /// Load providers from configuration
+    /// Shared metrics handle. Clone it into providers so the request path can
+    /// record requests, errors, retries, and token usage.
+    pub fn metrics(&self) -> Arc<RegistryMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Refresh the configured/healthy provider gauges from current state.
+    fn refresh_gauges(&self, inner: &RegistryInner) {
+        let configured = inner.providers.len() as u64;
+        let healthy = inner.providers.values().filter(|e| e.would_route()).count() as u64;
+        self.metrics.set_gauges(configured, healthy);
+    }
+
+    /// Load providers and their `[[models]]` routing table from configuration.
+    ///
+    /// Each provider is instantiated and registered by name; the `models`
+    /// blocks are then wired into the model → provider routing table via
+    /// [`Self::register_models`] so priority failover, weighted balancing, and
+    /// token-aware routing all operate on real config rather than an empty map.
     pub fn from_configs(
         configs: &[ProviderConfig],
+        models: &[ModelConfig],
         token_store: Option<TokenStore>,
     ) -> Result<Self, ProviderError> {
-        let mut registry = Self::new();
+        let registry = Self::new();
 
         for config in configs {
             // Skip disabled providers
@@ -39,218 +676,932 @@ impl ProviderRegistry {
                 continue;
             }
 
-            // Get API key - required for API key auth, skipped for OAuth
-            let api_key = match &config.auth_type {
-                super::AuthType::ApiKey => resolve_api_key(config)?,
-                super::AuthType::OAuth => {
-                    // OAuth providers will handle authentication differently
-                    // For now, use a placeholder - will be replaced with token
-                    config
-                        .oauth_provider
-                        .clone()
-                        .unwrap_or_else(|| config.name.clone())
-                }
-            };
+            let provider = build_provider(config, token_store.clone())?;
 
-            // Create provider instance based on type
-            let provider: Box<dyn AnthropicProvider> = match config.provider_type.as_str() {
-                // OpenAI
-                "openai" => Box::new(OpenAIProvider::new(
-                    config.name.clone(),
-                    api_key,
-                    config
-                        .base_url
-                        .clone()
-                        .unwrap_or_else(|| "https://api.openai.com/v1".to_string()),
-                    config.models.clone(),
-                    config.oauth_provider.clone(),
-                    token_store.clone(),
-                )),
-
-                // Anthropic-compatible providers
-                "anthropic" => Box::new(AnthropicCompatibleProvider::new(
-                    config.name.clone(),
-                    api_key,
-                    config
-                        .base_url
-                        .clone()
-                        .unwrap_or_else(|| "https://api.anthropic.com".to_string()),
-                    config.models.clone(),
-                    config.oauth_provider.clone(),
-                    token_store.clone(),
-                )),
-                "z.ai" => Box::new(AnthropicCompatibleProvider::zai(
-                    api_key,
-                    config.models.clone(),
-                    token_store.clone(),
-                )),
-                "minimax" => Box::new(AnthropicCompatibleProvider::minimax(
-                    api_key,
-                    config.models.clone(),
-                    token_store.clone(),
-                )),
-                "zenmux" => Box::new(AnthropicCompatibleProvider::zenmux(
-                    api_key,
-                    config.models.clone(),
-                    token_store.clone(),
-                )),
-                "kimi-coding" => Box::new(AnthropicCompatibleProvider::kimi_coding(
-                    api_key,
-                    config.models.clone(),
-                    token_store.clone(),
-                )),
-
-                // OpenAI-compatible providers
-                "openrouter" => Box::new(OpenAIProvider::openrouter(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "deepinfra" => Box::new(OpenAIProvider::deepinfra(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "novita" => Box::new(OpenAIProvider::novita(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "baseten" => Box::new(OpenAIProvider::baseten(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "together" => Box::new(OpenAIProvider::together(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "github-copilot" | "copilot" => Box::new(OpenAIProvider::github_copilot(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "fireworks" => Box::new(OpenAIProvider::fireworks(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "groq" => Box::new(OpenAIProvider::groq(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "nebius" => Box::new(OpenAIProvider::nebius(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "cerebras" => Box::new(OpenAIProvider::cerebras(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "moonshot" => Box::new(OpenAIProvider::moonshot(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "qwen" => Box::new(OpenAIProvider::qwen(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "gemini" => Box::new(OpenAIProvider::gemini(
-                    config.name.clone(),
-                    api_key,
-                    config.models.clone(),
-                )),
-                "longcat" => Box::new(OpenAIProvider::longcat(
-                    config.name.clone(),
-                    api_key,
-                    config
-                        .base_url
-                        .clone()
-                        .unwrap_or_else(|| "https://api.longcat.ai/v1".to_string()),
-                    config.models.clone(),
-                )),
-                "ollama" => Box::new(OpenAIProvider::ollama(
-                    config.name.clone(),
-                    api_key,
-                    config
-                        .base_url
-                        .clone()
-                        .unwrap_or_else(|| "http://localhost:11434/v1".to_string()),
-                    config.models.clone(),
-                )),
-                "lmstudio" => Box::new(OpenAIProvider::lmstudio(
-                    config.name.clone(),
-                    api_key,
-                    config
-                        .base_url
-                        .clone()
-                        .unwrap_or_else(|| "http://localhost:1234/v1".to_string()),
-                    config.models.clone(),
-                )),
-
-                other => {
-                    return Err(ProviderError::ConfigError(format!(
-                        "Unknown provider type: {}",
-                        other
-                    )));
-                }
+            // Model mappings come from the [[models]] section (wired below);
+            // here we only register the provider instance by name.
+            registry.inner.write().unwrap().providers.insert(
+                config.name.clone(),
+                ProviderEntry::new(provider, config.clone(), true),
+            );
+        }
+
+        registry.register_models(models);
+
+        let inner = registry.inner.read().unwrap();
+        registry.refresh_gauges(&inner);
+        drop(inner);
+
+        Ok(registry)
+    }
+
+    /// Wire `[[models]]` config blocks into the model → provider routing table.
+    pub fn register_models(&self, models: &[ModelConfig]) {
+        let mut inner = self.inner.write().unwrap();
+        for model in models {
+            for entry in &model.providers {
+                register_model_locked(
+                    &mut inner,
+                    &model.name,
+                    &entry.provider,
+                    ModelLimits {
+                        max_context_tokens: entry.max_context_tokens,
+                        max_output_tokens: entry.max_output_tokens,
+                    },
+                    entry.weight,
+                    entry.rate_cap,
+                );
+            }
+        }
+    }
+
+    /// Add (or replace) a provider at runtime from a [`ProviderConfig`].
+    ///
+    /// Returns the name the provider was registered under. The provider is
+    /// instantiated immediately so a misconfigured config surfaces its error
+    /// to the caller rather than at first dispatch.
+    pub fn add_provider(
+        &self,
+        config: &ProviderConfig,
+        token_store: Option<TokenStore>,
+    ) -> Result<String, ProviderError> {
+        let provider = build_provider(config, token_store)?;
+        let mut inner = self.inner.write().unwrap();
+        let enabled = config.is_enabled();
+        inner.providers.insert(
+            config.name.clone(),
+            ProviderEntry::new(provider, config.clone(), enabled),
+        );
+        self.refresh_gauges(&inner);
+        Ok(config.name.clone())
+    }
+
+    /// Remove a provider by name. Returns `true` if a provider was removed.
+    pub fn remove_provider(&self, name: &str) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let removed = inner.providers.remove(name).is_some();
+        if removed {
+            drop_provider_from_mappings(&mut inner, name);
+            self.refresh_gauges(&inner);
+        }
+        removed
+    }
+
+    /// Enable or disable a provider without dropping its instance, so it can be
+    /// taken out of rotation and brought back without rebuilding. Returns
+    /// `true` if the named provider exists.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let found = match inner.providers.get_mut(name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.refresh_gauges(&inner);
+        }
+        found
+    }
+
+    /// Render the current metrics in Prometheus text format. Backs `/metrics`.
+    pub fn render_metrics(&self) -> String {
+        self.metrics.render_prometheus()
+    }
+
+    /// Rebuild the provider set and `[[models]]` routing table in place from a
+    /// freshly loaded config.
+    ///
+    /// Diffs `configs` against the currently registered providers: new
+    /// providers are added, providers no longer present (or now disabled) are
+    /// dropped, and providers whose `api_key`, `base_url`, or `models` changed
+    /// — including a rotated `api_key_path` file whose contents differ — are
+    /// re-instantiated. Providers whose config is unchanged keep their live
+    /// instance, so in-flight sessions are never dropped on reload. This lets
+    /// rotated API-key files and refreshed OAuth tokens take effect without a
+    /// restart. A config-unchanged provider still has its `failure_threshold`
+    /// / `cooldown_secs` applied to its live breaker in place, so breaker
+    /// tuning takes effect without rebuilding the connection or losing the
+    /// breaker's current trip state.
+    ///
+    /// `models` is diffed the same way: any (model, provider) pair dropped from
+    /// the config is removed from the routing table, and the rest are
+    /// re-registered via [`Self::register_model`]'s core logic, so editing a
+    /// `[[models]]` block's weight, `rate_cap`, `max_context_tokens`, or
+    /// provider priority order on disk takes effect on reload too.
+    ///
+    /// Every provider that needs rebuilding is instantiated *before* anything
+    /// live is mutated: if any one of them fails (e.g. a rotated
+    /// `api_key_path` that transiently fails to read), this returns `Err`
+    /// with the registry completely untouched, rather than leaving some
+    /// providers removed/updated, others not, and the `[[models]]` diff never
+    /// applied — a reload either fully succeeds or is a no-op.
+    pub fn reload(
+        &self,
+        configs: &[ProviderConfig],
+        models: &[ModelConfig],
+        token_store: Option<TokenStore>,
+    ) -> Result<ReloadOutcome, ProviderError> {
+        let mut outcome = ReloadOutcome::default();
+        let mut inner = self.inner.write().unwrap();
+
+        // Build every provider that needs (re)instantiating up front. This is
+        // the only fallible part of a reload; nothing below is allowed to
+        // mutate `inner` until every build here has succeeded.
+        let mut built = Vec::new();
+        for config in configs.iter().filter(|c| c.is_enabled()) {
+            let needs_rebuild = match inner.providers.get(&config.name) {
+                Some(entry) => provider_needs_rebuild(&entry.config, config),
+                None => true,
             };
+            if needs_rebuild {
+                let is_update = inner.providers.contains_key(&config.name);
+                let provider = build_provider(config, token_store.clone())?;
+                built.push((config.clone(), provider, is_update));
+            }
+        }
 
-            // NOTE: models field in provider config is deprecated
-            // Model mappings are now defined in [[models]] section
-            // We only register the provider by name
+        // Everything from here on is infallible — apply it all.
+
+        // Drop providers that are gone from the new config or now disabled.
+        let desired: HashMap<&str, &ProviderConfig> = configs
+            .iter()
+            .filter(|c| c.is_enabled())
+            .map(|c| (c.name.as_str(), c))
+            .collect();
+        let removed: Vec<String> = inner
+            .providers
+            .keys()
+            .filter(|name| !desired.contains_key(name.as_str()))
+            .cloned()
+            .collect();
+        for name in removed {
+            inner.providers.remove(&name);
+            drop_provider_from_mappings(&mut inner, &name);
+            outcome.removed.push(name);
+        }
+
+        // Providers that didn't need a rebuild still get their breaker tuning
+        // applied in place.
+        for config in configs.iter().filter(|c| c.is_enabled()) {
+            if let Some(entry) = inner.providers.get_mut(&config.name) {
+                if !provider_needs_rebuild(&entry.config, config) {
+                    let threshold = config.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+                    let cooldown = config
+                        .cooldown_secs
+                        .map(Duration::from_secs)
+                        .unwrap_or(DEFAULT_COOLDOWN);
+                    entry.breaker.reconfigure(threshold, cooldown);
+                    entry.config = config.clone();
+                    outcome.unchanged.push(config.name.clone());
+                }
+            }
+        }
 
-            // Add provider to registry
-            registry
+        // Swap in the providers built above.
+        for (config, provider, is_update) in built {
+            let name = config.name.clone();
+            inner
                 .providers
-                .insert(config.name.clone(), Arc::new(provider));
+                .insert(name.clone(), ProviderEntry::new(provider, config, true));
+            if is_update {
+                outcome.reloaded.push(name);
+            } else {
+                outcome.added.push(name);
+            }
         }
 
-        Ok(registry)
+        // Diff the `[[models]]` table: drop (model, provider) pairs no longer
+        // present, then upsert the rest so weight/rate_cap/context-window
+        // changes and reordered provider priority actually take effect.
+        let desired_pairs: HashSet<(&str, &str)> = models
+            .iter()
+            .flat_map(|m| {
+                m.providers
+                    .iter()
+                    .map(move |p| (m.name.as_str(), p.provider.as_str()))
+            })
+            .collect();
+        for (model_name, candidates) in inner.model_to_provider.iter_mut() {
+            candidates.retain(|c| desired_pairs.contains(&(model_name.as_str(), c.provider.as_str())));
+        }
+        inner.model_to_provider.retain(|_, candidates| !candidates.is_empty());
+        inner
+            .model_limits
+            .retain(|(provider, model), _| desired_pairs.contains(&(model.as_str(), provider.as_str())));
+
+        for model in models {
+            for entry in &model.providers {
+                register_model_locked(
+                    &mut inner,
+                    &model.name,
+                    &entry.provider,
+                    ModelLimits {
+                        max_context_tokens: entry.max_context_tokens,
+                        max_output_tokens: entry.max_output_tokens,
+                    },
+                    entry.weight,
+                    entry.rate_cap,
+                );
+            }
+            // Upserting keeps surviving candidates where they were; reorder
+            // them to the block's listed priority.
+            if let Some(candidates) = inner.model_to_provider.get_mut(&model.name) {
+                candidates.sort_by_key(|c| {
+                    model.providers.iter().position(|p| p.provider == c.provider)
+                });
+            }
+        }
+
+        self.refresh_gauges(&inner);
+        drop(inner);
+
+        tracing::info!(
+            added = outcome.added.len(),
+            removed = outcome.removed.len(),
+            reloaded = outcome.reloaded.len(),
+            unchanged = outcome.unchanged.len(),
+            models = models.len(),
+            "provider registry reloaded"
+        );
+        Ok(outcome)
+    }
+
+    /// Spawn a background thread that polls `config_path` (and the
+    /// `api_key_path` of every currently registered provider) for mtime
+    /// changes every `poll_interval`, calling `load` to re-parse config from
+    /// disk and [`Self::reload`] to apply it whenever something changed.
+    ///
+    /// `load` is injected rather than this module parsing the config file
+    /// itself, since the on-disk format is owned elsewhere in the crate; it
+    /// receives `config_path` and returns the provider/model config to
+    /// reload with. A `load` or `reload` failure is logged and the watcher
+    /// keeps polling — a bad edit on disk must not kill the watch loop or
+    /// disturb the registry's last-known-good state.
+    pub fn watch_config(
+        self: &Arc<Self>,
+        config_path: PathBuf,
+        token_store: Option<TokenStore>,
+        poll_interval: Duration,
+        load: impl Fn(&std::path::Path) -> Result<(Vec<ProviderConfig>, Vec<ModelConfig>), ProviderError>
+            + Send
+            + 'static,
+    ) -> thread::JoinHandle<()> {
+        let registry = Arc::clone(self);
+        thread::spawn(move || {
+            let mut snapshot: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+            loop {
+                thread::sleep(poll_interval);
+
+                let mut watched = vec![config_path.clone()];
+                {
+                    let inner = registry.inner.read().unwrap();
+                    watched.extend(
+                        inner
+                            .providers
+                            .values()
+                            .filter_map(|e| e.config.api_key_path.as_deref())
+                            .filter_map(expand_home),
+                    );
+                }
+                let current: Vec<(PathBuf, Option<SystemTime>)> = watched
+                    .into_iter()
+                    .map(|path| {
+                        let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                        (path, mtime)
+                    })
+                    .collect();
+
+                if !config_paths_changed(&current, &mut snapshot) {
+                    continue;
+                }
+
+                match load(&config_path) {
+                    Ok((configs, models)) => {
+                        if let Err(e) = registry.reload(&configs, &models, token_store.clone()) {
+                            tracing::warn!(error = %e, "config reload failed; keeping previous state");
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "failed to parse config for reload");
+                    }
+                }
+            }
+        })
+    }
+
+    /// Admin view of configured providers and their model mappings.
+    ///
+    /// Backs `GET /admin/providers`.
+    pub fn describe_providers(&self) -> Value {
+        let inner = self.inner.read().unwrap();
+        let providers: Vec<Value> = inner
+            .providers
+            .values()
+            .map(|entry| {
+                let models: Vec<&String> = inner
+                    .model_to_provider
+                    .iter()
+                    .filter(|(_, candidates)| {
+                        candidates.iter().any(|c| c.provider == entry.config.name)
+                    })
+                    .map(|(m, _)| m)
+                    .collect();
+                json!({
+                    "name": entry.config.name,
+                    "provider_type": entry.config.provider_type,
+                    "enabled": entry.enabled,
+                    "models": models,
+                })
+            })
+            .collect();
+        json!({ "providers": providers })
+    }
+
+    /// Admin view of known model -> provider mappings.
+    ///
+    /// Backs `GET /admin/models`.
+    pub fn describe_models(&self) -> Value {
+        let inner = self.inner.read().unwrap();
+        let models: HashMap<&String, Vec<Value>> = inner
+            .model_to_provider
+            .iter()
+            .map(|(model, candidates)| {
+                let entries: Vec<Value> = candidates
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "provider": c.provider,
+                            "weight": c.weight,
+                            "rate_cap": c.rate_cap,
+                        })
+                    })
+                    .collect();
+                (model, entries)
+            })
+            .collect();
+        json!({ "models": models })
     }
 
     /// Get a provider by name
     pub fn get_provider(&self, name: &str) -> Option<Arc<Box<dyn AnthropicProvider>>> {
-        self.providers.get(name).cloned()
+        let inner = self.inner.read().unwrap();
+        inner
+            .providers
+            .get(name)
+            .filter(|entry| entry.enabled)
+            .map(|entry| entry.provider.clone())
     }
 
-    /// Get a provider for a specific model
+    /// Get the highest-priority available provider for a model and reserve its
+    /// dispatch slot — consuming the breaker's single half-open trial when the
+    /// chosen provider is recovering, so it won't be stampeded.
+    ///
+    /// Walks the priority order, returning the first candidate whose breaker
+    /// admits the request. For callers that need the full failover chain use
+    /// [`Self::get_providers_for_model`] with [`Self::try_begin_request`].
     pub fn get_provider_for_model(
         &self,
         model: &str,
     ) -> Result<Arc<Box<dyn AnthropicProvider>>, ProviderError> {
-        // First, check if we have a direct model â†’ provider mapping
-        if let Some(provider_name) = self.model_to_provider.get(model) {
-            if let Some(provider) = self.providers.get(provider_name) {
-                return Ok(provider.clone());
+        let inner = self.inner.read().unwrap();
+
+        // Explicit priority-ordered mapping takes precedence.
+        if let Some(mapped) = inner.model_to_provider.get(model) {
+            for candidate in mapped {
+                if let Some(entry) = inner.providers.get(&candidate.provider) {
+                    if entry.try_dispatch() {
+                        return Ok(entry.provider.clone());
+                    }
+                }
+            }
+        }
+
+        // Fall back to capability-based discovery when no mapped candidate
+        // could be reserved (or nothing is mapped).
+        for entry in inner.providers.values() {
+            if entry.provider.supports_model(model) && entry.try_dispatch() {
+                return Ok(entry.provider.clone());
+            }
+        }
+
+        Err(ProviderError::ModelNotSupported(model.to_string()))
+    }
+
+    /// Reserve a dispatch slot on `provider` immediately before the caller sends
+    /// it a request, consuming the breaker's single half-open trial when the
+    /// provider is recovering. Returns `false` if the breaker won't admit the
+    /// request right now (its trial is already in flight, or it is still open),
+    /// in which case the caller should move to the next failover candidate.
+    pub fn try_begin_request(&self, provider: &str) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.providers.get(provider).is_some_and(|e| e.try_dispatch())
+    }
+
+    /// Get every provider that can serve `model`, in priority order, skipping
+    /// any whose circuit breaker is currently open or whose `rate_cap` has no
+    /// budget left in the current window.
+    ///
+    /// The caller walks the returned candidates, reserving each with
+    /// [`Self::try_begin_request`] before dispatching and recording the outcome
+    /// with [`Self::record_success`] / [`Self::record_failure`], so transient
+    /// provider outages turn into silent failover rather than hard errors.
+    pub fn get_providers_for_model(&self, model: &str) -> Vec<Arc<Box<dyn AnthropicProvider>>> {
+        let inner = self.inner.read().unwrap();
+        let mut candidates = Vec::new();
+
+        // Explicit priority-ordered mapping takes precedence.
+        if let Some(mapped) = inner.model_to_provider.get(model) {
+            for candidate in mapped {
+                if let Some(entry) = inner.providers.get(&candidate.provider) {
+                    if entry.would_route() && candidate.rate_would_admit() {
+                        candidates.push(entry.provider.clone());
+                    }
+                }
+            }
+        }
+
+        // Fall back to capability-based discovery when no mapped candidate is
+        // available (or nothing is mapped).
+        if candidates.is_empty() {
+            for entry in inner.providers.values() {
+                if entry.would_route() && entry.provider.supports_model(model) {
+                    candidates.push(entry.provider.clone());
+                }
             }
         }
 
-        // If no direct mapping, search through all providers
-        for provider in self.providers.values() {
-            if provider.supports_model(model) {
-                return Ok(provider.clone());
+        candidates
+    }
+
+    /// Pick one provider for `model` using smooth weighted round-robin over the
+    /// healthy candidates, spreading traffic in proportion to each candidate's
+    /// configured weight, and reserve its dispatch slot before returning it.
+    ///
+    /// Providers whose circuit breaker is open are excluded from the pool, so
+    /// weighting composes with failover: an unhealthy provider simply stops
+    /// receiving its share until it recovers. The nominal winner's
+    /// [`ProviderEntry::try_dispatch`] can still lose a half-open trial race to
+    /// another caller, so candidates are walked in descending weight order and
+    /// the first to actually reserve both a dispatch slot and a `rate_cap`
+    /// window slot is returned — mirroring [`Self::get_provider_for_model`]'s
+    /// priority walk instead of trusting the side-effect-free `would_route`
+    /// check alone. Returns `ModelNotSupported` when no healthy candidate is
+    /// mapped or none can reserve a slot.
+    pub fn select_weighted(
+        &self,
+        model: &str,
+    ) -> Result<Arc<Box<dyn AnthropicProvider>>, ProviderError> {
+        let inner = self.inner.read().unwrap();
+        let mapped = inner
+            .model_to_provider
+            .get(model)
+            .ok_or_else(|| ProviderError::ModelNotSupported(model.to_string()))?;
+
+        // Healthy candidates only, keeping a handle to the entry for dispatch.
+        let healthy: Vec<&Candidate> = mapped
+            .iter()
+            .filter(|c| {
+                inner
+                    .providers
+                    .get(&c.provider)
+                    .is_some_and(|e| e.would_route())
+                    && c.rate_would_admit()
+            })
+            .collect();
+
+        // Smooth weighted round-robin: bump every healthy candidate's current
+        // weight by its static weight regardless of which one ends up
+        // dispatching, then rank by the updated value (highest bid first).
+        let total: i64 = healthy.iter().map(|c| c.weight as i64).sum();
+        let mut ranked: Vec<(&Candidate, i64)> = healthy
+            .iter()
+            .map(|&candidate| {
+                let updated = candidate
+                    .current_weight
+                    .fetch_add(candidate.weight as i64, Ordering::Relaxed)
+                    + candidate.weight as i64;
+                (candidate, updated)
+            })
+            .collect();
+        ranked.sort_by_key(|&(_, weight)| std::cmp::Reverse(weight));
+
+        for (candidate, _) in ranked {
+            let Some(entry) = inner.providers.get(&candidate.provider) else {
+                continue;
+            };
+            if !candidate.try_acquire_rate() {
+                continue;
+            }
+            if entry.try_dispatch() {
+                candidate.current_weight.fetch_sub(total, Ordering::Relaxed);
+                return Ok(entry.provider.clone());
             }
         }
 
         Err(ProviderError::ModelNotSupported(model.to_string()))
     }
 
+    /// Map a logical model name to a provider, appended to the model's
+    /// priority-ordered candidate list, recording the provider's context-window
+    /// metadata, its relative `weight` for weighted load balancing, and an
+    /// optional `rate_cap`. Re-registering an existing (model, provider) pair
+    /// updates its weight and rate cap in place.
+    pub fn register_model(
+        &self,
+        model: &str,
+        provider: &str,
+        limits: ModelLimits,
+        weight: u32,
+        rate_cap: Option<u32>,
+    ) {
+        let mut inner = self.inner.write().unwrap();
+        register_model_locked(&mut inner, model, provider, limits, weight, rate_cap);
+    }
+
+    /// Token-aware variant of [`Self::get_providers_for_model`]: estimate the
+    /// request's prompt token count and keep only candidates whose model's
+    /// `max_context_tokens` can hold it, preserving priority order.
+    ///
+    /// This routes an over-large request to a larger-context alternative mapped
+    /// to the same logical model name, and returns a descriptive error when no
+    /// candidate can fit the prompt.
+    pub fn get_providers_for_request(
+        &self,
+        model: &str,
+        payload: &Value,
+    ) -> Result<Vec<Arc<Box<dyn AnthropicProvider>>>, ProviderError> {
+        let estimated = estimate_payload_tokens(model, payload);
+        let inner = self.inner.read().unwrap();
+
+        let Some(mapped) = inner.model_to_provider.get(model) else {
+            return Err(ProviderError::ModelNotSupported(model.to_string()));
+        };
+
+        let mut candidates = Vec::new();
+        let mut largest_window = 0usize;
+        for candidate in mapped {
+            let Some(entry) = inner.providers.get(&candidate.provider) else {
+                continue;
+            };
+            if !entry.would_route() {
+                continue;
+            }
+            if let Some(limits) = inner
+                .model_limits
+                .get(&(candidate.provider.clone(), model.to_string()))
+            {
+                largest_window = largest_window.max(limits.max_context_tokens);
+                if limits.max_context_tokens < estimated {
+                    continue;
+                }
+            }
+            candidates.push(entry.provider.clone());
+        }
+
+        if candidates.is_empty() {
+            return Err(ProviderError::ConfigError(format!(
+                "Request for '{}' needs ~{} tokens but no available provider fits it \
+                 (largest context window {} tokens)",
+                model, estimated, largest_window
+            )));
+        }
+
+        Ok(candidates)
+    }
+
+    /// Record a successful request against a provider's circuit breaker.
+    pub fn record_success(&self, provider: &str) {
+        let inner = self.inner.read().unwrap();
+        if let Some(entry) = inner.providers.get(provider) {
+            entry.breaker.record_success();
+            self.refresh_gauges(&inner);
+        }
+    }
+
+    /// Record a failed request against a provider's circuit breaker, tripping
+    /// it once consecutive failures cross the threshold.
+    pub fn record_failure(&self, provider: &str, model: &str) {
+        let inner = self.inner.read().unwrap();
+        if let Some(entry) = inner.providers.get(provider) {
+            entry.breaker.record_failure();
+            self.metrics.record_error(&entry.config.name, model);
+            self.refresh_gauges(&inner);
+        }
+    }
+
+    /// Record a dispatched request for `provider`/`model`.
+    ///
+    /// Called by the dispatch path on the single provider a request is actually
+    /// sent to — enumerating or weighting candidates does *not* count a request,
+    /// so `ccmux_requests_total` reflects real dispatches rather than the length
+    /// of the failover list.
+    pub fn record_request(&self, provider: &str, model: &str) {
+        self.metrics.record_request(provider, model);
+    }
+
+    /// Record a retry attempt on `provider`/`model`.
+    pub fn record_retry(&self, provider: &str, model: &str) {
+        self.metrics.record_retry(provider, model);
+    }
+
+    /// Observe request latency in milliseconds for `provider`/`model`.
+    pub fn observe_latency_ms(&self, provider: &str, model: &str, ms: u64) {
+        self.metrics.observe_latency_ms(provider, model, ms);
+    }
+
+    /// Parse prompt/completion token usage from a provider response and record
+    /// it against the (provider, model) series. Anthropic responses carry
+    /// `usage.input_tokens`/`usage.output_tokens`; OpenAI-style responses carry
+    /// `usage.prompt_tokens`/`usage.completion_tokens` — both shapes are read.
+    pub fn record_response_usage(&self, provider: &str, model: &str, response: &Value) {
+        let usage = response.get("usage");
+        let field = |names: &[&str]| -> u64 {
+            usage
+                .and_then(|u| names.iter().find_map(|n| u.get(*n)))
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0)
+        };
+        let prompt = field(&["input_tokens", "prompt_tokens"]);
+        let completion = field(&["output_tokens", "completion_tokens"]);
+        if prompt > 0 || completion > 0 {
+            self.metrics.record_tokens(provider, model, prompt, completion);
+        }
+    }
+
     /// List all available models
     pub fn list_models(&self) -> Vec<String> {
-        self.model_to_provider.keys().cloned().collect()
+        let inner = self.inner.read().unwrap();
+        inner.model_to_provider.keys().cloned().collect()
     }
 
     /// List all providers
     pub fn list_providers(&self) -> Vec<String> {
-        self.providers.keys().cloned().collect()
+        let inner = self.inner.read().unwrap();
+        inner.providers.keys().cloned().collect()
+    }
+}
+
+/// Instantiate a single provider from its [`ProviderConfig`].
+///
+/// Factored out of `from_configs` so one config can be built on demand — both
+/// at startup and when an operator adds a provider at runtime through the
+/// admin API.
+fn build_provider(
+    config: &ProviderConfig,
+    token_store: Option<TokenStore>,
+) -> Result<Box<dyn AnthropicProvider>, ProviderError> {
+    // Get API key - required for API key auth, skipped for OAuth
+    let api_key = match &config.auth_type {
+        super::AuthType::ApiKey => resolve_api_key(config)?,
+        super::AuthType::OAuth => {
+            // OAuth providers will handle authentication differently
+            // For now, use a placeholder - will be replaced with token
+            config
+                .oauth_provider
+                .clone()
+                .unwrap_or_else(|| config.name.clone())
+        }
+    };
+
+    // Create provider instance based on type
+    let provider: Box<dyn AnthropicProvider> = match config.provider_type.as_str() {
+        // OpenAI
+        "openai" => Box::new(OpenAIProvider::new(
+            config.name.clone(),
+            api_key,
+            config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "https://api.openai.com/v1".to_string()),
+            config.models.clone(),
+            config.oauth_provider.clone(),
+            token_store.clone(),
+        )),
+
+        // Anthropic-compatible providers
+        "anthropic" => Box::new(AnthropicCompatibleProvider::new(
+            config.name.clone(),
+            api_key,
+            config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "https://api.anthropic.com".to_string()),
+            config.models.clone(),
+            config.oauth_provider.clone(),
+            token_store.clone(),
+        )),
+        "z.ai" => Box::new(AnthropicCompatibleProvider::zai(
+            api_key,
+            config.models.clone(),
+            token_store.clone(),
+        )),
+        "minimax" => Box::new(AnthropicCompatibleProvider::minimax(
+            api_key,
+            config.models.clone(),
+            token_store.clone(),
+        )),
+        "zenmux" => Box::new(AnthropicCompatibleProvider::zenmux(
+            api_key,
+            config.models.clone(),
+            token_store.clone(),
+        )),
+        "kimi-coding" => Box::new(AnthropicCompatibleProvider::kimi_coding(
+            api_key,
+            config.models.clone(),
+            token_store.clone(),
+        )),
+
+        // OpenAI-compatible providers
+        "openrouter" => Box::new(OpenAIProvider::openrouter(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "deepinfra" => Box::new(OpenAIProvider::deepinfra(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "novita" => Box::new(OpenAIProvider::novita(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "baseten" => Box::new(OpenAIProvider::baseten(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "together" => Box::new(OpenAIProvider::together(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "github-copilot" | "copilot" => Box::new(OpenAIProvider::github_copilot(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "fireworks" => Box::new(OpenAIProvider::fireworks(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "groq" => Box::new(OpenAIProvider::groq(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "nebius" => Box::new(OpenAIProvider::nebius(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "cerebras" => Box::new(OpenAIProvider::cerebras(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "moonshot" => Box::new(OpenAIProvider::moonshot(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "qwen" => Box::new(OpenAIProvider::qwen(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "gemini" => Box::new(OpenAIProvider::gemini(
+            config.name.clone(),
+            api_key,
+            config.models.clone(),
+        )),
+        "longcat" => Box::new(OpenAIProvider::longcat(
+            config.name.clone(),
+            api_key,
+            config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "https://api.longcat.ai/v1".to_string()),
+            config.models.clone(),
+        )),
+        "ollama" => Box::new(OpenAIProvider::ollama(
+            config.name.clone(),
+            api_key,
+            config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "http://localhost:11434/v1".to_string()),
+            config.models.clone(),
+        )),
+        "lmstudio" => Box::new(OpenAIProvider::lmstudio(
+            config.name.clone(),
+            api_key,
+            config
+                .base_url
+                .clone()
+                .unwrap_or_else(|| "http://localhost:1234/v1".to_string()),
+            config.models.clone(),
+        )),
+
+        other => {
+            return Err(ProviderError::ConfigError(format!(
+                "Unknown provider type: {}",
+                other
+            )));
+        }
+    };
+
+    Ok(provider)
+}
+
+/// Core of [`ProviderRegistry::register_model`]/[`ProviderRegistry::register_models`],
+/// factored out so [`ProviderRegistry::reload`] can upsert the `[[models]]` table
+/// under the same write-lock guard it uses to diff providers.
+fn register_model_locked(
+    inner: &mut RegistryInner,
+    model: &str,
+    provider: &str,
+    limits: ModelLimits,
+    weight: u32,
+    rate_cap: Option<u32>,
+) {
+    let candidates = inner.model_to_provider.entry(model.to_string()).or_default();
+    match candidates.iter_mut().find(|c| c.provider == provider) {
+        Some(existing) => {
+            existing.weight = weight.max(1);
+            existing.rate_cap = rate_cap;
+            existing.rate_limiter = rate_cap.map(RateLimiter::new);
+        }
+        None => candidates.push(Candidate::new(provider.to_string(), weight, rate_cap)),
     }
+    inner
+        .model_limits
+        .insert((provider.to_string(), model.to_string()), limits);
+}
+
+/// Drop a provider name from every model's candidate list and its context-window
+/// metadata, removing any model entry left with no providers.
+fn drop_provider_from_mappings(inner: &mut RegistryInner, name: &str) {
+    for candidates in inner.model_to_provider.values_mut() {
+        candidates.retain(|c| c.provider != name);
+    }
+    inner
+        .model_to_provider
+        .retain(|_, candidates| !candidates.is_empty());
+    inner.model_limits.retain(|(provider, _), _| provider != name);
+}
+
+/// Summary of a [`ProviderRegistry::reload`] pass, reported back to the caller
+/// (and suitable for an admin/log response).
+#[derive(Debug, Default)]
+pub struct ReloadOutcome {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub reloaded: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+/// Decide whether a live provider must be rebuilt because its credentials or
+/// endpoint changed. Compares the connection-relevant fields and, when the key
+/// comes from a file, the resolved contents so a rotated `api_key_path` is
+/// picked up even though the path itself is unchanged.
+fn provider_needs_rebuild(old: &ProviderConfig, new: &ProviderConfig) -> bool {
+    if old.base_url != new.base_url
+        || old.models != new.models
+        || old.api_key != new.api_key
+        || old.api_key_path != new.api_key_path
+    {
+        return true;
+    }
+
+    // Same path but possibly rotated contents on disk.
+    if new.api_key_path.is_some() {
+        return resolve_api_key(old).ok() != resolve_api_key(new).ok();
+    }
+
+    false
+}
+
+/// Compare `current` mtimes against `snapshot`, updating `snapshot` in place,
+/// and report whether anything changed. Factored out of
+/// [`ProviderRegistry::watch_config`]'s poll loop so the change-detection
+/// logic is unit-testable without real files or a running thread.
+fn config_paths_changed(
+    current: &[(PathBuf, Option<SystemTime>)],
+    snapshot: &mut HashMap<PathBuf, Option<SystemTime>>,
+) -> bool {
+    let mut changed = false;
+    for (path, mtime) in current {
+        if snapshot.get(path) != Some(mtime) {
+            changed = true;
+        }
+        snapshot.insert(path.clone(), *mtime);
+    }
+    changed
 }
 
 /// Resolve API key from direct value or CLI auth JSON path
@@ -370,4 +1721,398 @@ mod tests {
         let result = registry.get_provider_for_model("gpt-4");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_register_models_wires_routing_table() {
+        let registry = ProviderRegistry::new();
+        registry.register_models(&[ModelConfig {
+            name: "big".to_string(),
+            providers: vec![
+                ModelProviderConfig {
+                    provider: "primary".to_string(),
+                    weight: 3,
+                    rate_cap: None,
+                    max_context_tokens: 200_000,
+                    max_output_tokens: Some(8192),
+                },
+                ModelProviderConfig {
+                    provider: "secondary".to_string(),
+                    weight: 1,
+                    rate_cap: Some(10),
+                    max_context_tokens: 32_000,
+                    max_output_tokens: None,
+                },
+            ],
+        }]);
+
+        assert_eq!(registry.list_models(), vec!["big".to_string()]);
+
+        let inner = registry.inner.read().unwrap();
+        let candidates = inner.model_to_provider.get("big").unwrap();
+        // Priority order from config is preserved.
+        assert_eq!(candidates[0].provider, "primary");
+        assert_eq!(candidates[0].weight, 3);
+        assert_eq!(candidates[1].provider, "secondary");
+        assert_eq!(candidates[1].rate_cap, Some(10));
+        // Context-window metadata is recorded per (provider, model).
+        assert_eq!(
+            inner
+                .model_limits
+                .get(&("primary".to_string(), "big".to_string()))
+                .unwrap()
+                .max_context_tokens,
+            200_000
+        );
+    }
+
+    #[test]
+    fn test_reload_diffs_and_reregisters_model_table() {
+        let registry = ProviderRegistry::new();
+        registry.register_models(&[ModelConfig {
+            name: "big".to_string(),
+            providers: vec![
+                ModelProviderConfig {
+                    provider: "primary".to_string(),
+                    weight: 1,
+                    rate_cap: None,
+                    max_context_tokens: 32_000,
+                    max_output_tokens: None,
+                },
+                ModelProviderConfig {
+                    provider: "secondary".to_string(),
+                    weight: 1,
+                    rate_cap: None,
+                    max_context_tokens: 32_000,
+                    max_output_tokens: None,
+                },
+            ],
+        }]);
+
+        // Reload with no provider configs (nothing to build), but a `[[models]]`
+        // table that bumps "primary"'s context window and drops "secondary".
+        let outcome = registry
+            .reload(
+                &[],
+                &[ModelConfig {
+                    name: "big".to_string(),
+                    providers: vec![ModelProviderConfig {
+                        provider: "primary".to_string(),
+                        weight: 5,
+                        rate_cap: None,
+                        max_context_tokens: 200_000,
+                        max_output_tokens: None,
+                    }],
+                }],
+                None,
+            )
+            .unwrap();
+        assert_eq!(outcome.added.len() + outcome.removed.len(), 0);
+
+        let inner = registry.inner.read().unwrap();
+        let candidates = inner.model_to_provider.get("big").unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].provider, "primary");
+        assert_eq!(candidates[0].weight, 5);
+        assert_eq!(
+            inner
+                .model_limits
+                .get(&("primary".to_string(), "big".to_string()))
+                .unwrap()
+                .max_context_tokens,
+            200_000
+        );
+        assert!(!inner
+            .model_limits
+            .contains_key(&("secondary".to_string(), "big".to_string())));
+    }
+
+    fn openrouter_config(name: &str) -> ProviderConfig {
+        ProviderConfig {
+            name: name.to_string(),
+            provider_type: "openrouter".to_string(),
+            auth_type: crate::providers::AuthType::ApiKey,
+            api_key: Some("test-key".to_string()),
+            api_key_path: None,
+            base_url: None,
+            models: vec!["gpt-4".to_string()],
+            oauth_provider: None,
+            enabled: None,
+            failure_threshold: None,
+            cooldown_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_reload_is_atomic_on_a_bad_provider_config() {
+        let registry = ProviderRegistry::new();
+        registry
+            .add_provider(&openrouter_config("keep"), None)
+            .unwrap();
+        registry
+            .add_provider(&openrouter_config("doomed"), None)
+            .unwrap();
+
+        // "doomed" is dropped from the new config (so it would be removed),
+        // and "bad" has an unbuildable provider_type. The whole reload must
+        // fail without touching anything, leaving both original providers in
+        // place.
+        let mut bad = openrouter_config("bad");
+        bad.provider_type = "not-a-real-provider-type".to_string();
+        let result = registry.reload(&[openrouter_config("keep"), bad], &[], None);
+        assert!(result.is_err());
+
+        let mut providers = registry.list_providers();
+        providers.sort();
+        assert_eq!(providers, vec!["doomed".to_string(), "keep".to_string()]);
+    }
+
+    #[test]
+    fn test_reload_unchanged_provider_still_gets_breaker_tuning() {
+        let registry = ProviderRegistry::new();
+        registry
+            .add_provider(&openrouter_config("keep"), None)
+            .unwrap();
+
+        let mut tuned = openrouter_config("keep");
+        tuned.failure_threshold = Some(1);
+        let outcome = registry.reload(&[tuned], &[], None).unwrap();
+        assert_eq!(outcome.unchanged, vec!["keep".to_string()]);
+        assert!(outcome.reloaded.is_empty());
+
+        let inner = registry.inner.read().unwrap();
+        let entry = inner.providers.get("keep").unwrap();
+        assert_eq!(entry.breaker.threshold, 1);
+    }
+
+    #[test]
+    fn test_config_paths_changed_detects_mtime_diffs() {
+        let mut snapshot = HashMap::new();
+        let path = PathBuf::from("/tmp/fake-ccmux-config.toml");
+        let t1 = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(100));
+        let t2 = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(200));
+
+        // First observation is always a change (nothing in the snapshot yet).
+        assert!(config_paths_changed(&[(path.clone(), t1)], &mut snapshot));
+        // Same mtime again: no change.
+        assert!(!config_paths_changed(&[(path.clone(), t1)], &mut snapshot));
+        // mtime moved forward: a change.
+        assert!(config_paths_changed(&[(path.clone(), t2)], &mut snapshot));
+        // A path that fails to stat (None) is tracked like any other value.
+        assert!(config_paths_changed(&[(path.clone(), None)], &mut snapshot));
+        assert!(!config_paths_changed(&[(path, None)], &mut snapshot));
+    }
+
+    #[test]
+    fn test_prometheus_emits_help_type_once_per_metric() {
+        let metrics = RegistryMetrics::default();
+        metrics.record_request("p1", "m");
+        metrics.record_request("p2", "m");
+        let out = metrics.render_prometheus();
+        // With two series the HELP/TYPE header must still appear exactly once.
+        assert_eq!(out.matches("# HELP ccmux_requests_total").count(), 1);
+        assert_eq!(
+            out.matches("# TYPE ccmux_requests_total counter").count(),
+            1
+        );
+        // Both series' samples are present.
+        assert!(out.contains("ccmux_requests_total{provider_name=\"p1\",model=\"m\"} 1"));
+        assert!(out.contains("ccmux_requests_total{provider_name=\"p2\",model=\"m\"} 1"));
+    }
+
+    #[test]
+    fn test_latency_histogram_emits_a_bucket_row() {
+        let metrics = RegistryMetrics::default();
+        metrics.observe_latency_ms("p1", "m", 42);
+        let out = metrics.render_prometheus();
+        // A histogram must carry at least one `_bucket` row alongside
+        // `_sum`/`_count`; we don't track real boundaries, so a single `+Inf`
+        // bucket (every observation falls in it) is the minimum valid form.
+        assert!(out.contains("ccmux_latency_ms_bucket{provider_name=\"p1\",model=\"m\",le=\"+Inf\"} 1"));
+        assert!(out.contains("ccmux_latency_ms_sum{provider_name=\"p1\",model=\"m\"} 42"));
+        assert!(out.contains("ccmux_latency_ms_count{provider_name=\"p1\",model=\"m\"} 1"));
+    }
+
+    #[test]
+    fn test_record_response_usage_parses_tokens() {
+        let registry = ProviderRegistry::new();
+        registry.record_response_usage(
+            "anthropic",
+            "claude",
+            &json!({ "usage": { "input_tokens": 12, "output_tokens": 5 } }),
+        );
+        // OpenAI-style field names are read too.
+        registry.record_response_usage(
+            "openai",
+            "gpt-4",
+            &json!({ "usage": { "prompt_tokens": 7, "completion_tokens": 3 } }),
+        );
+        let out = registry.render_metrics();
+        assert!(out
+            .contains("ccmux_prompt_tokens_total{provider_name=\"anthropic\",model=\"claude\"} 12"));
+        assert!(out.contains(
+            "ccmux_completion_tokens_total{provider_name=\"anthropic\",model=\"claude\"} 5"
+        ));
+        assert!(
+            out.contains("ccmux_prompt_tokens_total{provider_name=\"openai\",model=\"gpt-4\"} 7")
+        );
+    }
+
+    #[test]
+    fn test_breaker_threshold_and_cooldown_are_configurable() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(5));
+        breaker.record_failure();
+        // Below the configured threshold of 2: still admitting.
+        assert!(breaker.would_admit());
+        breaker.record_failure();
+        // Threshold of 2 crossed: tripped.
+        assert!(!breaker.would_admit());
+        match *breaker.state.lock().unwrap() {
+            BreakerState::Open { cooldown, .. } => assert_eq!(cooldown, Duration::from_secs(5)),
+            _ => panic!("breaker should be open after crossing its configured threshold"),
+        };
+    }
+
+    #[test]
+    fn test_breaker_reconfigure_applies_without_resetting_trip_state() {
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(5));
+        breaker.record_failure();
+        // Tripped under the old threshold of 1.
+        assert!(!breaker.would_admit());
+        breaker.reconfigure(10, Duration::from_secs(60));
+        // Reconfiguring doesn't clear the existing trip — it only changes
+        // what governs future trips/cooldowns.
+        assert!(!breaker.would_admit());
+        match *breaker.state.lock().unwrap() {
+            BreakerState::Open { cooldown, .. } => assert_eq!(cooldown, Duration::from_secs(5)),
+            _ => panic!("reconfigure must not reset an already-open breaker's cooldown"),
+        };
+    }
+
+    #[test]
+    fn test_breaker_opens_after_threshold_and_blocks() {
+        let breaker = CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN);
+        assert!(breaker.would_admit());
+        for _ in 0..DEFAULT_FAILURE_THRESHOLD {
+            breaker.record_failure();
+        }
+        // Tripped; cooldown has not elapsed so no request is admitted.
+        assert!(!breaker.would_admit());
+        assert!(!breaker.try_acquire());
+    }
+
+    #[test]
+    fn test_breaker_half_open_admits_single_trial() {
+        let breaker = CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN);
+        for _ in 0..DEFAULT_FAILURE_THRESHOLD {
+            breaker.record_failure();
+        }
+        // Force the cooldown to have elapsed.
+        *breaker.state.lock().unwrap() = BreakerState::Open {
+            opened_at: Instant::now() - Duration::from_secs(60),
+            cooldown: DEFAULT_COOLDOWN,
+        };
+        // would_admit is side-effect-free: it must not consume the trial.
+        assert!(breaker.would_admit());
+        // Exactly one trial request is admitted.
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+    }
+
+    #[test]
+    fn test_breaker_reopen_doubles_cooldown_and_success_closes() {
+        let breaker = CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN);
+        for _ in 0..DEFAULT_FAILURE_THRESHOLD {
+            breaker.record_failure();
+        }
+        *breaker.state.lock().unwrap() = BreakerState::Open {
+            opened_at: Instant::now() - Duration::from_secs(60),
+            cooldown: DEFAULT_COOLDOWN,
+        };
+        assert!(breaker.try_acquire());
+        // A failed half-open trial re-opens with a doubled cooldown.
+        breaker.record_failure();
+        match *breaker.state.lock().unwrap() {
+            BreakerState::Open { cooldown, .. } => assert_eq!(cooldown, DEFAULT_COOLDOWN * 2),
+            _ => panic!("breaker should be open after failed trial"),
+        }
+        // A later successful trial closes it and resets the count.
+        *breaker.state.lock().unwrap() = BreakerState::HalfOpen {
+            cooldown: DEFAULT_COOLDOWN * 2,
+        };
+        breaker.record_success();
+        assert!(matches!(
+            *breaker.state.lock().unwrap(),
+            BreakerState::Closed
+        ));
+        assert!(breaker.would_admit());
+    }
+
+    #[test]
+    fn test_rate_limiter_enforces_cap_per_window() {
+        let limiter = RateLimiter::new(2);
+        assert!(limiter.peek());
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        // Cap reached: side-effect-free peek agrees, and no further slot is granted.
+        assert!(!limiter.peek());
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_rate_limiter_rolls_window_over() {
+        let limiter = RateLimiter::new(1);
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        // Force the window to look like it started over a second ago.
+        limiter.window.lock().unwrap().0 = Instant::now() - Duration::from_secs(2);
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_candidate_rate_would_admit_is_side_effect_free() {
+        let candidate = Candidate::new("only".to_string(), 1, Some(1));
+        // Peeking repeatedly must not consume the single slot.
+        assert!(candidate.rate_would_admit());
+        assert!(candidate.rate_would_admit());
+        assert!(candidate.try_acquire_rate());
+        assert!(!candidate.rate_would_admit());
+        assert!(!candidate.try_acquire_rate());
+    }
+
+    #[test]
+    fn test_candidate_without_rate_cap_is_always_admitted() {
+        let candidate = Candidate::new("only".to_string(), 1, None);
+        for _ in 0..100 {
+            assert!(candidate.rate_would_admit());
+            assert!(candidate.try_acquire_rate());
+        }
+    }
+
+    #[test]
+    fn test_collect_text_recurses_into_tool_result_content() {
+        let payload = json!({
+            "messages": [{
+                "role": "user",
+                "content": [{
+                    "type": "tool_result",
+                    "content": [{ "type": "text", "text": "word ".repeat(1000) }]
+                }]
+            }]
+        });
+        // The nested tool_result payload must be counted, not skipped.
+        let estimated = estimate_payload_tokens("claude-3", &payload);
+        assert!(estimated > 500, "expected a large estimate, got {estimated}");
+    }
+
+    #[test]
+    fn test_bpe_counts_cjk_per_character() {
+        let tokenizer = BpeTokenizer;
+        // Latin text still segments on whitespace.
+        assert_eq!(tokenizer.count_tokens("hello world"), 2);
+        // CJK ideographs must not collapse into a single word token.
+        assert_eq!(tokenizer.count_tokens("你好世界啊"), 5);
+        // A large CJK prompt therefore estimates well above 1 token, so it is
+        // not mistaken for a tiny request by the context-window fit filter.
+        assert!(tokenizer.count_tokens(&"漢".repeat(100)) >= 100);
+    }
 }
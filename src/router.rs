@@ -0,0 +1,109 @@
+//! HTTP routes for the proxy's control plane: the `/admin/*` provider-management
+//! API and the `/metrics` Prometheus endpoint. The Anthropic/OpenAI-compatible
+//! request-forwarding routes are mounted separately; this module only covers
+//! the operator-facing surface backed by [`ProviderRegistry`].
+
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+
+use crate::providers::{ProviderConfig, ProviderRegistry};
+
+/// Build the admin + metrics router, mounted under the proxy's main [`Router`].
+///
+/// Exposes:
+/// - `GET /admin/providers` — configured providers and their model mappings
+/// - `POST /admin/providers` — add (or replace) a provider from a JSON [`ProviderConfig`]
+/// - `DELETE /admin/providers/{name}` — remove a provider
+/// - `POST /admin/providers/{name}/enable` / `.../disable` — toggle a provider without rebuilding it
+/// - `GET /admin/models` — known model → provider mappings
+/// - `GET /metrics` — Prometheus text exposition
+pub fn admin_router(registry: Arc<ProviderRegistry>) -> Router {
+    Router::new()
+        .route(
+            "/admin/providers",
+            get(list_providers).post(add_provider),
+        )
+        .route("/admin/providers/{name}", axum::routing::delete(remove_provider))
+        .route("/admin/providers/{name}/enable", axum::routing::post(enable_provider))
+        .route("/admin/providers/{name}/disable", axum::routing::post(disable_provider))
+        .route("/admin/models", get(list_models))
+        .route("/metrics", get(metrics))
+        .with_state(registry)
+}
+
+async fn list_providers(State(registry): State<Arc<ProviderRegistry>>) -> Json<Value> {
+    Json(registry.describe_providers())
+}
+
+async fn list_models(State(registry): State<Arc<ProviderRegistry>>) -> Json<Value> {
+    Json(registry.describe_models())
+}
+
+async fn add_provider(
+    State(registry): State<Arc<ProviderRegistry>>,
+    Json(config): Json<ProviderConfig>,
+) -> Response {
+    // Admin-added providers carry no OAuth token store; operators adding a
+    // provider this way are expected to use API-key auth.
+    match registry.add_provider(&config, None) {
+        Ok(name) => (StatusCode::OK, Json(json!({ "name": name }))).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+    }
+}
+
+async fn remove_provider(
+    State(registry): State<Arc<ProviderRegistry>>,
+    Path(name): Path<String>,
+) -> Response {
+    if registry.remove_provider(&name) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        not_found(&name)
+    }
+}
+
+async fn enable_provider(
+    State(registry): State<Arc<ProviderRegistry>>,
+    Path(name): Path<String>,
+) -> Response {
+    set_enabled(registry, name, true)
+}
+
+async fn disable_provider(
+    State(registry): State<Arc<ProviderRegistry>>,
+    Path(name): Path<String>,
+) -> Response {
+    set_enabled(registry, name, false)
+}
+
+fn set_enabled(registry: Arc<ProviderRegistry>, name: String, enabled: bool) -> Response {
+    if registry.set_enabled(&name, enabled) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        not_found(&name)
+    }
+}
+
+async fn metrics(State(registry): State<Arc<ProviderRegistry>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        registry.render_metrics(),
+    )
+}
+
+fn not_found(provider: &str) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        &format!("unknown provider '{provider}'"),
+    )
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}